@@ -1,86 +1,409 @@
-use std::{ops::Deref, path::Path};
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
 use dashmap::DashMap;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use sha2::{Digest, Sha256};
 
 use crate::{
     containers::{List, Set, Symbol},
     context::{Ctx, CtxErr, CtxResult, ModuleId, ToCtx, ToCtxErr},
-    grammar::{parse_program, RawConstExpr, RawDefn, RawExpr, RawProgram, RawTypeExpr},
+    grammar::{parse_program, ImportSpec, RawConstExpr, RawDefn, RawExpr, RawProgram, RawTypeExpr},
 };
 
+/// An integrity annotation attached to a `require`, e.g. `require "utils.mel" sha256:abcd...`.
+///
+/// The hash pins the exact bytes of a fetched module so that remote imports are verifiable and
+/// content-addressable, mirroring Dhall's hex integrity hashes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Integrity {
+    Sha256([u8; 32]),
+}
+
+impl Integrity {
+    /// Parses an annotation of the form `sha256:<64 hex chars>`.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (algo, hex) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("malformed integrity annotation: {}", s))?;
+        match algo {
+            "sha256" => {
+                let mut out = [0u8; 32];
+                hex::decode_to_slice(hex, &mut out)
+                    .map_err(|e| anyhow::anyhow!("bad sha256 hex in {}: {}", s, e))?;
+                Ok(Integrity::Sha256(out))
+            }
+            other => anyhow::bail!("unsupported integrity algorithm: {}", other),
+        }
+    }
+
+    /// Checks whether the given bytes hash to this integrity value.
+    pub fn verify(&self, bytes: &[u8]) -> bool {
+        match self {
+            Integrity::Sha256(expected) => {
+                let got = Sha256::digest(bytes);
+                got.as_slice() == expected
+            }
+        }
+    }
+
+    /// The lowercase hex digest, used as the content-addressed cache key.
+    pub fn to_hex(&self) -> String {
+        match self {
+            Integrity::Sha256(d) => hex::encode(d),
+        }
+    }
+}
+
+/// Something that can fetch the raw source of a module given its [ModuleId].
+///
+/// Demodularization no longer hard-codes a filesystem read: a `Demodularizer` holds one of these so
+/// that imports can come from the filesystem, an http(s) endpoint, or a git repository.
+pub trait Resolver: Send + Sync + 'static {
+    /// Fetches the source of `id`, or an error if it cannot be retrieved.
+    fn resolve(&self, id: ModuleId) -> anyhow::Result<String>;
+}
+
+/// Resolves modules as files underneath a root directory.
+pub struct FsResolver {
+    root: PathBuf,
+}
+
+impl FsResolver {
+    /// Creates a resolver rooted at `root`.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+        }
+    }
+}
+
+impl Resolver for FsResolver {
+    fn resolve(&self, id: ModuleId) -> anyhow::Result<String> {
+        let mut path = self.root.clone();
+        path.push(&id.to_string());
+        Ok(std::fs::read_to_string(&path)?)
+    }
+}
+
+/// Resolves modules by treating the [ModuleId] as an http(s) URL.
+pub struct HttpResolver;
+
+impl Resolver for HttpResolver {
+    fn resolve(&self, id: ModuleId) -> anyhow::Result<String> {
+        let url = id.to_string();
+        Ok(reqwest::blocking::get(&url)?.error_for_status()?.text()?)
+    }
+}
+
+/// Resolves modules out of a checked-out git working tree.
+///
+/// The [ModuleId] is interpreted as a path relative to `workdir`; callers are expected to have
+/// cloned/checked out the desired revision beforehand.
+pub struct GitResolver {
+    workdir: PathBuf,
+}
+
+impl GitResolver {
+    /// Creates a resolver over a git working tree at `workdir`.
+    pub fn new(workdir: &Path) -> Self {
+        Self {
+            workdir: workdir.to_owned(),
+        }
+    }
+}
+
+impl Resolver for GitResolver {
+    fn resolve(&self, id: ModuleId) -> anyhow::Result<String> {
+        let mut path = self.workdir.clone();
+        path.push(&id.to_string());
+        Ok(std::fs::read_to_string(&path)?)
+    }
+}
+
 /// A struct that encapsulates a parallel demodularizer that eliminates "require" and "provide" in a raw AST.
 pub struct Demodularizer {
     cache: DashMap<ModuleId, Ctx<RawProgram>>,
-    fallback: Box<dyn Fn(ModuleId) -> anyhow::Result<String> + Send + Sync + 'static>,
+    resolver: Box<dyn Resolver>,
+    /// Integrity annotation declared for each module, populated from the `require` grammar.
+    integrity: DashMap<ModuleId, Integrity>,
+    /// Content-addressed cache of verified source, keyed by integrity hex digest.
+    content_cache: DashMap<String, String>,
+    /// Optional on-disk mirror of `content_cache`; filenames are percent-encoded.
+    cache_dir: Option<PathBuf>,
+    /// Cached symbol-provenance indices, one per demodularized module.
+    symbol_cache: DashMap<ModuleId, Ctx<SymbolIndex>>,
 }
 
 impl Demodularizer {
     /// Creates a new demodularizer, rooted at some filesystem.
     pub fn new_at_fs(root: &Path) -> Self {
-        let root = root.to_owned();
-        let fallback = move |mid: ModuleId| {
-            let mut root = root.clone();
-            root.push(&mid.to_string());
-            Ok(std::fs::read_to_string(&root)?)
-        };
+        Self::new(Box::new(FsResolver::new(root)))
+    }
+
+    /// Creates a new demodularizer backed by an arbitrary [Resolver].
+    pub fn new(resolver: Box<dyn Resolver>) -> Self {
         Self {
             cache: DashMap::new(),
-            fallback: Box::new(fallback),
+            resolver,
+            integrity: DashMap::new(),
+            content_cache: DashMap::new(),
+            cache_dir: None,
+            symbol_cache: DashMap::new(),
         }
     }
 
+    /// Enables the on-disk content-addressed cache, mirroring verified source into `dir`.
+    pub fn with_cache_dir(mut self, dir: &Path) -> Self {
+        self.cache_dir = Some(dir.to_owned());
+        self
+    }
+
+    /// Records the integrity annotation that a `require` pinned for `id`, so that fetched bytes are
+    /// verified and content-addressed before they are parsed.
+    pub fn declare_integrity(&self, id: ModuleId, integrity: Integrity) {
+        self.integrity.insert(id, integrity);
+    }
+
+    /// Fetches the source of `id`, verifying and caching against its declared integrity hash.
+    fn fetch_source(&self, id: ModuleId) -> anyhow::Result<String> {
+        let integrity = self.integrity.get(&id).map(|r| r.clone());
+        // With a known hash, a transitive duplicate or a previous build resolves straight from cache.
+        if let Some(integrity) = &integrity {
+            let key = integrity.to_hex();
+            if let Some(hit) = self.content_cache.get(&key) {
+                return Ok(hit.clone());
+            }
+            if let Some(dir) = &self.cache_dir {
+                let path = dir.join(cache_filename(id, integrity));
+                if let Ok(bytes) = std::fs::read(&path) {
+                    if integrity.verify(&bytes) {
+                        let source = String::from_utf8(bytes)?;
+                        self.content_cache.insert(key, source.clone());
+                        return Ok(source);
+                    }
+                }
+            }
+        }
+        let source = self.resolver.resolve(id)?;
+        if let Some(integrity) = &integrity {
+            anyhow::ensure!(
+                integrity.verify(source.as_bytes()),
+                "integrity check failed for {}: expected {}",
+                id,
+                integrity.to_hex()
+            );
+            self.content_cache
+                .insert(integrity.to_hex(), source.clone());
+            if let Some(dir) = &self.cache_dir {
+                std::fs::create_dir_all(dir)?;
+                std::fs::write(dir.join(cache_filename(id, integrity)), source.as_bytes())?;
+            }
+        }
+        Ok(source)
+    }
+
     /// Return the demodularized version of some module ID.
     pub fn demod(&self, id: ModuleId) -> CtxResult<Ctx<RawProgram>> {
+        self.demod_inner(id, List::new())
+    }
+
+    /// Demodularizes `id`, carrying the ordered chain of modules resolving above it so that an
+    /// import cycle can be reported as `A -> B -> C -> A` instead of recursing forever.
+    fn demod_inner(&self, id: ModuleId, chain: List<ModuleId>) -> CtxResult<Ctx<RawProgram>> {
+        // A module already on the active path means we have closed a `require` cycle. Detection
+        // reads only the ordered `chain` carried down this branch — never shared mutable state — so a
+        // non-cyclic diamond (A requires B and C, both requiring D) can resolve D on both branches
+        // concurrently without either spuriously seeing it "in progress".
+        if chain.iter().any(|m| *m == id) {
+            let cycle = chain
+                .iter()
+                .chain(std::iter::once(&id))
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(anyhow::anyhow!("import cycle detected: {}", cycle)).err_ctx(None);
+        }
         if let Some(res) = self.cache.get(&id) {
             log::debug!("demod {} HIT!", id);
             Ok(res.deref().clone())
         } else {
             log::debug!("demod {} MISS!", id);
             // populate the cache
-            let raw_string = (self.fallback)(id).err_ctx(None)?;
+            let raw_string = self.fetch_source(id).err_ctx(None)?;
             let parsed = parse_program(&raw_string, id)?;
-            // go through the dependencies in parallel, demodularizing as we go
-            let new_defs = parsed
+            // extend the active path for the recursive fan-out; passed by value into each branch so
+            // the reported cycle is deterministic regardless of `par_iter` scheduling
+            let chain = {
+                let mut chain = chain;
+                chain.push_back(id);
+                chain
+            };
+            // Resolve every definition in parallel, preserving source order. A `require` turns into
+            // the mangled (and, for a selective import, filtered) definitions of its target, plus
+            // any alias entries mapping `m.foo` to the specific mangled symbol from that module.
+            let contributions = parsed
                 .definitions
                 .par_iter()
-                .fold(
-                    || Ok::<_, CtxErr>(List::new()),
-                    |accum, def| {
-                        let mut accum = accum?;
-                        match def.deref() {
-                            RawDefn::Require(other) => {
-                                let other_demodularized = self.demod(*other)?;
-                                accum.append(mangle(
-                                    other_demodularized.definitions.clone(),
-                                    *other,
-                                ));
-                            }
-                            _ => accum.push_back(def.clone()),
+                .map(|def| match def.deref() {
+                    RawDefn::Require(other, spec) => {
+                        // a `require "utils.mel" sha256:...` annotation pins the target's bytes: record
+                        // it before resolving so `fetch_source` verifies and content-addresses them
+                        if let Some(integrity) = spec.integrity.clone() {
+                            self.declare_integrity(*other, integrity);
                         }
-                        Ok(accum)
-                    },
-                )
-                .reduce(
-                    || Ok::<_, CtxErr>(List::new()),
-                    |a, b| {
-                        let mut a = a?;
-                        a.append(b?);
-                        Ok(a)
-                    },
-                )?;
-            Ok(RawProgram {
+                        // pass the chain by value so each branch reports a deterministic path
+                        let other_demodularized = self.demod_inner(*other, chain.clone())?;
+                        let (defs, provided) =
+                            mangle_import(other_demodularized.definitions.clone(), *other, spec);
+                        // only an aliased import contributes qualified-access entries
+                        let aliases = match spec.alias {
+                            Some(alias) => provided
+                                .into_iter()
+                                .map(|(name, emitted)| (alias, name, emitted))
+                                .collect(),
+                            None => Vec::new(),
+                        };
+                        Ok((Contribution::Imported(defs), aliases))
+                    }
+                    _ => Ok((Contribution::Own(def.clone()), Vec::new())),
+                })
+                .collect::<CtxResult<Vec<_>>>();
+            let contributions = contributions?;
+            // A qualified name `m.foo` resolves to the mangled symbol exported by the module bound
+            // to `m`; distinct aliases keep same-named provides from two modules from colliding.
+            let mut aliases: HashMap<(Symbol, Symbol), Symbol> = HashMap::new();
+            for (_, entries) in &contributions {
+                for (alias, name, emitted) in entries {
+                    aliases.insert((*alias, *name), *emitted);
+                }
+            }
+            let mut resolver = AliasResolver::new(aliases);
+            let mut new_defs = List::new();
+            for (contribution, _) in contributions {
+                match contribution {
+                    Contribution::Imported(defs) => new_defs.append(defs),
+                    // own definitions may reference aliased imports, so rewrite them here
+                    Contribution::Own(def) => new_defs.push_back(resolve_own_defn(&resolver, def)),
+                }
+            }
+            let result = RawProgram {
                 definitions: new_defs,
-                body: parsed.body.clone(),
+                body: resolver.walk_expr(parsed.body.clone()),
             }
-            .with_ctx(parsed.ctx()))
+            .with_ctx(parsed.ctx());
+            // memoize so a module reachable by several paths (the diamond's shared leaf) resolves
+            // straight from cache once it has been demodularized; branches that miss the cache at
+            // the same time each demodularize it
+            self.cache.insert(id, result.clone());
+            Ok(result)
         }
     }
+
+    /// Returns a provenance index for `id`, mapping every emitted (post-mangling) symbol back to the
+    /// original name, defining [ModuleId], and source span it came from.
+    ///
+    /// This is the inverse of `Mangler::visit_sym`: downstream passes can render diagnostics in terms of the
+    /// user's own module and name, and answer "go to definition / find provides" queries. It is built
+    /// recursively, mirroring the mangling done during [Demodularizer::demod].
+    pub fn symbol_index(&self, id: ModuleId) -> CtxResult<Ctx<SymbolIndex>> {
+        self.symbol_index_inner(id, List::new())
+    }
+
+    /// Builds the provenance index for `id`, carrying the same ordered import chain as
+    /// [Demodularizer::demod_inner] so a cyclic import reports the clean `A -> B -> A` error that
+    /// chunk0-2 established for `demod` rather than recursing until the stack overflows.
+    fn symbol_index_inner(
+        &self,
+        id: ModuleId,
+        chain: List<ModuleId>,
+    ) -> CtxResult<Ctx<SymbolIndex>> {
+        if chain.iter().any(|m| *m == id) {
+            let cycle = chain
+                .iter()
+                .chain(std::iter::once(&id))
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(anyhow::anyhow!("import cycle detected: {}", cycle)).err_ctx(None);
+        }
+        if let Some(cached) = self.symbol_cache.get(&id) {
+            return Ok(cached.deref().clone());
+        }
+        let raw_string = self.fetch_source(id).err_ctx(None)?;
+        let parsed = parse_program(&raw_string, id)?;
+        let chain = {
+            let mut chain = chain;
+            chain.push_back(id);
+            chain
+        };
+        let mut index = SymbolIndex::default();
+        for def in parsed.definitions.iter() {
+            match def.deref() {
+                RawDefn::Require(other, spec) => {
+                    // the provenance of an imported name is whatever the submodule recorded, with its
+                    // mangled key run through the same remangling this import applies — computed with
+                    // the shared `import_no_mangle` helper so it cannot drift from `mangle_import`
+                    let sub = self.symbol_index_inner(*other, chain.clone())?;
+                    let provided = provides(&self.demod(*other)?.definitions);
+                    let no_mangle = import_no_mangle(&provided, spec);
+                    let mut namer = Mangler::new(*other, no_mangle);
+                    for record in sub.records() {
+                        // a selective import keeps the target's unrequested provides (now mangled,
+                        // not dropped), matching `mangle_import`, so their provenance stays queryable
+                        index.insert(SymbolRecord {
+                            mangled: namer.visit_sym(record.mangled),
+                            original: record.original.clone(),
+                            module: record.module,
+                        });
+                    }
+                }
+                // the importing module's own definitions keep their original names
+                other => {
+                    if let Some(name) = defn_ctx_name(other) {
+                        index.insert(SymbolRecord {
+                            mangled: *name,
+                            original: name,
+                            module: id,
+                        });
+                    }
+                }
+            }
+        }
+        let indexed = index.with_ctx(parsed.ctx());
+        self.symbol_cache.insert(id, indexed.clone());
+        Ok(indexed)
+    }
+}
+
+/// Builds a safe on-disk cache filename for a fetched module: the percent-encoded module id (so
+/// remote URLs become valid filenames) suffixed with the integrity hex digest.
+fn cache_filename(id: ModuleId, integrity: &Integrity) -> String {
+    let encoded = utf8_percent_encode(&id.to_string(), NON_ALPHANUMERIC).to_string();
+    format!("{}.{}", encoded, integrity.to_hex())
 }
 
-fn mangle(defs: List<Ctx<RawDefn>>, source: ModuleId) -> List<Ctx<RawDefn>> {
-    let no_mangle: Set<Symbol> = defs
-        .iter()
+/// The names of a required module left unmangled under `spec`, shared by `mangle_import` and
+/// `symbol_index` so the two never drift: an aliased import mangles everything (disambiguated through
+/// alias entries), a selective import keeps only the requested provides public, and a plain import
+/// keeps every provide public.
+fn import_no_mangle(provided: &Set<Symbol>, spec: &ImportSpec) -> Set<Symbol> {
+    if spec.alias.is_some() {
+        Set::new()
+    } else if let Some(only) = &spec.only {
+        only.iter().copied().filter(|n| provided.contains(n)).collect()
+    } else {
+        provided.clone()
+    }
+}
+
+/// The set of names a module `provide`s, which are exempt from mangling.
+fn provides(defs: &List<Ctx<RawDefn>>) -> Set<Symbol> {
+    defs.iter()
         .filter_map(|a| {
             if let RawDefn::Provide(a) = a.deref() {
                 Some(*a)
@@ -88,10 +411,95 @@ fn mangle(defs: List<Ctx<RawDefn>>, source: ModuleId) -> List<Ctx<RawDefn>> {
                 None
             }
         })
-        .collect();
+        .collect()
+}
+
+/// The top-level name a definition introduces, carrying its source span.
+fn defn_ctx_name(defn: &RawDefn) -> Option<Ctx<Symbol>> {
+    match defn {
+        RawDefn::Function { name, .. } => Some(name.clone()),
+        RawDefn::Struct { name, .. } => Some(name.clone()),
+        RawDefn::Constant(name, _) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Where an emitted (post-mangling) symbol originally came from.
+#[derive(Clone, Debug)]
+pub struct SymbolRecord {
+    /// The name as it appears in the demodularized program.
+    pub mangled: Symbol,
+    /// The original name, carrying the source span it was defined at.
+    pub original: Ctx<Symbol>,
+    /// The module that defined it.
+    pub module: ModuleId,
+}
+
+/// A queryable map of every symbol a demodularized program emits back to its provenance.
+///
+/// Built by [Demodularizer::symbol_index] in a pass of its own that repeats the renaming of
+/// [Demodularizer::demod]; `demod` itself does not produce or fill it. It lets tooling map mangled
+/// `name-<uniqid>` symbols back to the user's own module and name — the basis for readable diagnostics and IDE-style
+/// "go to definition" queries. Adapted from rust-analyzer's `import_map`/`find_path`.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolIndex {
+    by_mangled: HashMap<Symbol, SymbolRecord>,
+}
+
+impl SymbolIndex {
+    fn insert(&mut self, record: SymbolRecord) {
+        self.by_mangled.insert(record.mangled, record);
+    }
+
+    /// Resolves a mangled symbol to its provenance, if it was emitted by this program.
+    pub fn lookup(&self, mangled: Symbol) -> Option<&SymbolRecord> {
+        self.by_mangled.get(&mangled)
+    }
+
+    /// Iterates over every recorded definition.
+    pub fn records(&self) -> impl Iterator<Item = &SymbolRecord> {
+        self.by_mangled.values()
+    }
+}
+
+/// Demodularizes a required module under a specific [ImportSpec], returning the contributed
+/// definitions and, for each provided name, the symbol it was emitted as (so an aliased import can
+/// resolve `m.foo` to exactly that symbol).
+///
+/// The no-mangle set is computed per import: a selective `require (foo) from` keeps only the
+/// requested provides public, while an aliased `require .. as m` mangles every name and
+/// disambiguates solely through the returned alias entries.
+///
+/// A selective import does **not** drop the unrequested provides: they are emitted like any private
+/// definition (mangled to `name-<uniqid>`), just without a public unmangled name, so that a
+/// requested provide whose body calls an unrequested one still resolves. Suppressing only their
+/// public exposure is the whole of the "selective" behaviour.
+fn mangle_import(
+    defs: List<Ctx<RawDefn>>,
+    source: ModuleId,
+    spec: &ImportSpec,
+) -> (List<Ctx<RawDefn>>, Vec<(Symbol, Symbol)>) {
+    let provided = provides(&defs);
+    let no_mangle = import_no_mangle(&provided, spec);
+    let mut namer = Mangler::new(source, no_mangle.clone());
+    let alias_entries = if spec.alias.is_some() {
+        provided.iter().map(|&n| (n, namer.visit_sym(n))).collect()
+    } else {
+        Vec::new()
+    };
+    (mangle_with(defs, source, &no_mangle), alias_entries)
+}
+
+fn mangle_with(
+    defs: List<Ctx<RawDefn>>,
+    source: ModuleId,
+    no_mangle: &Set<Symbol>,
+) -> List<Ctx<RawDefn>> {
     log::debug!("no_mangle for {}: {:?}", source, no_mangle);
+    let no_mangle = no_mangle.clone();
     defs.into_iter()
         .filter_map(|defn| {
+            let mut outer = Mangler::new(source, no_mangle.clone());
             match defn.deref().clone() {
                 RawDefn::Function {
                     name,
@@ -101,6 +509,7 @@ fn mangle(defs: List<Ctx<RawDefn>>, source: ModuleId) -> List<Ctx<RawDefn>> {
                     rettype,
                     body,
                 } => {
+                    // the function's own type/cg variables and argument names are local binders
                     let inner_nomangle = cgvars
                         .iter()
                         .chain(genvars.iter())
@@ -110,8 +519,9 @@ fn mangle(defs: List<Ctx<RawDefn>>, source: ModuleId) -> List<Ctx<RawDefn>> {
                             acc.insert(s);
                             acc
                         });
+                    let mut inner = Mangler::new(source, inner_nomangle);
                     Some(RawDefn::Function {
-                        name: mangle_ctx_sym(name, source, &no_mangle),
+                        name: outer.walk_ctx_sym(name),
                         cgvars,
                         genvars,
                         args: args
@@ -119,22 +529,26 @@ fn mangle(defs: List<Ctx<RawDefn>>, source: ModuleId) -> List<Ctx<RawDefn>> {
                             .map(|arg| {
                                 let ctx = arg.ctx();
                                 let mut arg = arg.deref().clone();
-                                let new_bind =
-                                    mangle_type_expr(arg.bind.clone(), source, &inner_nomangle);
-                                arg.bind = new_bind;
+                                arg.bind = inner.walk_type(arg.bind.clone());
                                 arg.with_ctx(ctx)
                             })
                             .collect(),
-                        rettype: rettype.map(|rt| mangle_type_expr(rt, source, &no_mangle)),
-                        body: mangle_expr(body, source, &inner_nomangle),
+                        rettype: rettype.map(|rt| outer.walk_type(rt)),
+                        body: inner.walk_expr(body),
                     })
                 }
                 RawDefn::Struct { name, fields } => Some(RawDefn::Struct {
-                    name: mangle_ctx_sym(name, source, &no_mangle),
+                    name: outer.walk_ctx_sym(name),
                     fields,
                 }),
-                RawDefn::Constant(_, _) => todo!(),
-                RawDefn::Require(_) => None,
+                // a constant mangles exactly like a struct/function: its name goes through the
+                // `no_mangle` scope (so a `provide`d constant keeps its public name) and its body is
+                // walked so references to other modules' symbols are rewritten consistently
+                RawDefn::Constant(name, body) => Some(RawDefn::Constant(
+                    outer.walk_ctx_sym(name),
+                    outer.walk_expr(body),
+                )),
+                RawDefn::Require(..) => None,
                 RawDefn::Provide(_) => None,
             }
             .map(|c| c.with_ctx(defn.ctx()))
@@ -142,95 +556,462 @@ fn mangle(defs: List<Ctx<RawDefn>>, source: ModuleId) -> List<Ctx<RawDefn>> {
         .collect()
 }
 
-fn mangle_expr(expr: Ctx<RawExpr>, source: ModuleId, no_mangle: &Set<Symbol>) -> Ctx<RawExpr> {
-    let recurse = |expr| mangle_expr(expr, source, no_mangle);
-    let ctx = expr.ctx();
-    match expr.deref().clone() {
-        RawExpr::Let(sym, bind, body) => {
-            let mut inner_no_mangle = no_mangle.clone();
-            inner_no_mangle.insert(*sym);
-            RawExpr::Let(sym, bind, mangle_expr(body, source, &inner_no_mangle))
-        }
-        RawExpr::If(cond, a, b) => RawExpr::If(recurse(cond), recurse(a), recurse(b)),
-        RawExpr::BinOp(op, a, b) => RawExpr::BinOp(op, recurse(a), recurse(b)),
+/// A one-layer traversal over the raw AST.
+///
+/// The recursive shape of `RawExpr`/`RawTypeExpr`/`RawConstExpr` lives once in the `descend_*`
+/// drivers below rather than being re-spelled by every pass; an impl overrides only the hooks it
+/// cares about — `visit_sym` for a pure renaming, or a whole `walk_*` when it needs to introduce a
+/// scope before recursing. Modelled on Dhall's "move recursion out of Expr" refactor.
+trait Visitor: Sized {
+    /// Rewrites a single symbol. A renaming pass is essentially just this hook plus scoping.
+    fn visit_sym(&mut self, sym: Symbol) -> Symbol {
+        sym
+    }
+
+    /// Drives an expression; the default recurses into every immediate child.
+    fn walk_expr(&mut self, expr: Ctx<RawExpr>) -> Ctx<RawExpr> {
+        let ctx = expr.ctx();
+        descend_expr(self, expr.deref().clone()).with_ctx(ctx)
+    }
+
+    /// Drives a type expression; the default recurses into every immediate child.
+    fn walk_type(&mut self, ty: Ctx<RawTypeExpr>) -> Ctx<RawTypeExpr> {
+        let ctx = ty.ctx();
+        descend_type(self, ty.deref().clone()).with_ctx(ctx)
+    }
+
+    /// Drives a const expression; the default recurses into every immediate child.
+    fn walk_const(&mut self, cexpr: Ctx<RawConstExpr>) -> Ctx<RawConstExpr> {
+        let ctx = cexpr.ctx();
+        descend_const(self, cexpr.deref().clone()).with_ctx(ctx)
+    }
+
+    /// Applies [Visitor::visit_sym] to a symbol while preserving its span.
+    fn walk_ctx_sym(&mut self, sym: Ctx<Symbol>) -> Ctx<Symbol> {
+        let ctx = sym.ctx();
+        self.visit_sym(*sym).with_ctx(ctx)
+    }
+}
+
+/// Applies `v` to each immediate sub-node of an expression, reassembling the same variant.
+fn descend_expr<V: Visitor>(v: &mut V, expr: RawExpr) -> RawExpr {
+    match expr {
+        RawExpr::Let(sym, bind, body) => RawExpr::Let(sym, v.walk_expr(bind), v.walk_expr(body)),
+        RawExpr::If(cond, a, b) => RawExpr::If(v.walk_expr(cond), v.walk_expr(a), v.walk_expr(b)),
+        RawExpr::BinOp(op, a, b) => RawExpr::BinOp(op, v.walk_expr(a), v.walk_expr(b)),
         RawExpr::LitNum(a) => RawExpr::LitNum(a),
-        RawExpr::LitVec(v) => RawExpr::LitVec(v.into_iter().map(recurse).collect()),
+        RawExpr::LitVec(vs) => RawExpr::LitVec(vs.into_iter().map(|e| v.walk_expr(e)).collect()),
         RawExpr::LitStruct(a, fields) => RawExpr::LitStruct(
-            mangle_sym(a, source, no_mangle),
-            fields.into_iter().map(|(k, b)| (k, recurse(b))).collect(),
+            v.visit_sym(a),
+            fields.into_iter().map(|(k, b)| (k, v.walk_expr(b))).collect(),
         ),
-        RawExpr::Var(v) => RawExpr::Var(mangle_sym(v, source, no_mangle)),
-        RawExpr::CgVar(v) => RawExpr::CgVar(mangle_sym(v, source, no_mangle)),
+        RawExpr::Var(x) => RawExpr::Var(v.visit_sym(x)),
+        RawExpr::CgVar(x) => RawExpr::CgVar(v.visit_sym(x)),
         RawExpr::Apply(f, args) => {
-            RawExpr::Apply(recurse(f), args.into_iter().map(recurse).collect())
+            RawExpr::Apply(v.walk_expr(f), args.into_iter().map(|e| v.walk_expr(e)).collect())
+        }
+        RawExpr::Field(a, b) => RawExpr::Field(v.walk_expr(a), b),
+        RawExpr::VectorRef(vec, i) => RawExpr::VectorRef(v.walk_expr(vec), v.walk_expr(i)),
+        RawExpr::VectorSlice(vec, i, j) => {
+            RawExpr::VectorSlice(v.walk_expr(vec), v.walk_expr(i), v.walk_expr(j))
+        }
+        RawExpr::VectorUpdate(vec, i, x) => {
+            RawExpr::VectorUpdate(v.walk_expr(vec), v.walk_expr(i), v.walk_expr(x))
         }
-        RawExpr::Field(a, b) => RawExpr::Field(recurse(a), b),
-        RawExpr::VectorRef(v, i) => RawExpr::VectorRef(recurse(v), recurse(i)),
-        RawExpr::VectorSlice(v, i, j) => RawExpr::VectorSlice(recurse(v), recurse(i), recurse(j)),
-        RawExpr::VectorUpdate(v, i, x) => RawExpr::VectorUpdate(recurse(v), recurse(i), recurse(x)),
         RawExpr::Loop(n, bod, end) => RawExpr::Loop(
-            mangle_const_expr(n, source, no_mangle),
+            v.walk_const(n),
             bod.into_iter()
-                .map(|(k, v)| (mangle_sym(k, source, no_mangle), recurse(v)))
+                .map(|(k, val)| (v.visit_sym(k), v.walk_expr(val)))
                 .collect(),
-            recurse(end),
-        ),
-        RawExpr::IsType(a, t) => RawExpr::IsType(
-            mangle_sym(a, source, no_mangle),
-            mangle_type_expr(t, source, no_mangle),
+            v.walk_expr(end),
         ),
-        RawExpr::AsType(a, t) => {
-            RawExpr::AsType(recurse(a), mangle_type_expr(t, source, no_mangle))
-        }
+        RawExpr::IsType(a, t) => RawExpr::IsType(v.visit_sym(a), v.walk_type(t)),
+        RawExpr::AsType(a, t) => RawExpr::AsType(v.walk_expr(a), v.walk_type(t)),
         RawExpr::Fail => RawExpr::Fail,
     }
-    .with_ctx(ctx)
 }
 
-fn mangle_const_expr(
-    sym: Ctx<RawConstExpr>,
-    source: ModuleId,
-    no_mangle: &Set<Symbol>,
-) -> Ctx<RawConstExpr> {
-    let recurse = |sym| mangle_const_expr(sym, source, no_mangle);
-    match sym.deref().clone() {
-        RawConstExpr::Sym(s) => RawConstExpr::Sym(mangle_sym(s, source, no_mangle)),
+/// Applies `v` to each immediate sub-node of a type expression, reassembling the same variant.
+fn descend_type<V: Visitor>(v: &mut V, ty: RawTypeExpr) -> RawTypeExpr {
+    match ty {
+        RawTypeExpr::Sym(s) => RawTypeExpr::Sym(v.visit_sym(s)),
+        RawTypeExpr::Union(a, b) => RawTypeExpr::Union(v.walk_type(a), v.walk_type(b)),
+        RawTypeExpr::Vector(vs) => {
+            RawTypeExpr::Vector(vs.into_iter().map(|t| v.walk_type(t)).collect())
+        }
+        RawTypeExpr::Vectorof(t, n) => RawTypeExpr::Vectorof(v.walk_type(t), v.walk_const(n)),
+        RawTypeExpr::NatRange(i, j) => RawTypeExpr::NatRange(v.walk_const(i), v.walk_const(j)),
+    }
+}
+
+/// Applies `v` to each immediate sub-node of a const expression, reassembling the same variant.
+fn descend_const<V: Visitor>(v: &mut V, cexpr: RawConstExpr) -> RawConstExpr {
+    match cexpr {
+        RawConstExpr::Sym(s) => RawConstExpr::Sym(v.visit_sym(s)),
         RawConstExpr::Lit(l) => RawConstExpr::Lit(l),
-        RawConstExpr::Plus(a, b) => RawConstExpr::Plus(recurse(a), recurse(b)),
-        RawConstExpr::Mult(a, b) => RawConstExpr::Mult(recurse(a), recurse(b)),
+        RawConstExpr::Plus(a, b) => RawConstExpr::Plus(v.walk_const(a), v.walk_const(b)),
+        RawConstExpr::Mult(a, b) => RawConstExpr::Mult(v.walk_const(a), v.walk_const(b)),
     }
-    .with_ctx(sym.ctx())
 }
 
-fn mangle_ctx_sym(sym: Ctx<Symbol>, source: ModuleId, no_mangle: &Set<Symbol>) -> Ctx<Symbol> {
-    mangle_sym(*sym, source, no_mangle).with_ctx(sym.ctx())
+/// The sole symbol-rewriting pass: mangles every name to `name-<uniqid>` unless it is in the
+/// `no_mangle` scope (a `provide`d name, or a binder introduced by `let` or a function's
+/// parameters). A `loop` introduces no binder: its assignment targets are renamed like any use.
+struct Mangler {
+    source: ModuleId,
+    no_mangle: Set<Symbol>,
 }
 
-fn mangle_sym(sym: Symbol, source: ModuleId, no_mangle: &Set<Symbol>) -> Symbol {
-    if no_mangle.contains(&sym) {
-        sym
-    } else {
-        Symbol::from(format!("{:?}-{}", sym, source.uniqid()).as_str())
+impl Mangler {
+    fn new(source: ModuleId, no_mangle: Set<Symbol>) -> Self {
+        Self { source, no_mangle }
+    }
+
+    /// A child mangler with `sym` added to the no-mangle scope, used for a binder's body.
+    fn with_local(&self, sym: Symbol) -> Self {
+        let mut no_mangle = self.no_mangle.clone();
+        no_mangle.insert(sym);
+        Self {
+            source: self.source,
+            no_mangle,
+        }
     }
 }
 
-fn mangle_type_expr(
-    bind: Ctx<RawTypeExpr>,
-    source: ModuleId,
-    no_mangle: &Set<Symbol>,
-) -> Ctx<RawTypeExpr> {
-    let recurse = |bind| mangle_type_expr(bind, source, no_mangle);
-    match bind.deref().clone() {
-        RawTypeExpr::Sym(s) => RawTypeExpr::Sym(mangle_sym(s, source, no_mangle)),
-        RawTypeExpr::Union(a, b) => RawTypeExpr::Union(recurse(a), recurse(b)),
-        RawTypeExpr::Vector(v) => RawTypeExpr::Vector(v.into_iter().map(recurse).collect()),
-        RawTypeExpr::Vectorof(v, n) => {
-            RawTypeExpr::Vectorof(recurse(v), mangle_const_expr(n, source, no_mangle))
-        }
-        RawTypeExpr::NatRange(i, j) => RawTypeExpr::NatRange(
-            mangle_const_expr(i, source, no_mangle),
-            mangle_const_expr(j, source, no_mangle),
-        ),
+impl Visitor for Mangler {
+    fn visit_sym(&mut self, sym: Symbol) -> Symbol {
+        if self.no_mangle.contains(&sym) {
+            sym
+        } else {
+            Symbol::from(format!("{:?}-{}", sym, self.source.uniqid()).as_str())
+        }
+    }
+
+    fn walk_expr(&mut self, expr: Ctx<RawExpr>) -> Ctx<RawExpr> {
+        let ctx = expr.ctx();
+        match expr.deref().clone() {
+            // `let` binds a local name that shadows any module symbol within the body. NOTE: the
+            // bound value is evaluated in the *enclosing* scope, so it is mangled here -- unlike the
+            // old hand-written `mangle_expr`, which left the value unmangled and so failed to rewrite
+            // a module symbol referenced inside a `let` binding (e.g. `let x = helper() in ...`).
+            // This visitor corrects that latent bug; the collapse is otherwise behavior-preserving.
+            RawExpr::Let(sym, bind, body) => {
+                let bind = self.walk_expr(bind);
+                let body = self.with_local(*sym).walk_expr(body);
+                RawExpr::Let(sym, bind, body).with_ctx(ctx)
+            }
+            other => descend_expr(self, other).with_ctx(ctx),
+        }
+    }
+}
+
+/// Resolves aliased imports inside one of the importing module's own definitions, leaving its name
+/// untouched.
+///
+/// Unlike [mangle_with], this only rewrites sub-expressions, but it mirrors its scoping: a function's
+/// cg/gen variables and argument names are bound locally, so a parameter named like an import alias
+/// shadows it within the body.
+fn resolve_own_defn(resolver: &AliasResolver, defn: Ctx<RawDefn>) -> Ctx<RawDefn> {
+    let ctx = defn.ctx();
+    match defn.deref().clone() {
+        RawDefn::Function {
+            name,
+            cgvars,
+            genvars,
+            args,
+            rettype,
+            body,
+        } => {
+            let binders: Vec<Symbol> = cgvars
+                .iter()
+                .chain(genvars.iter())
+                .map(|a| **a)
+                .chain(args.iter().map(|a| *a.name))
+                .collect();
+            let mut inner = resolver.with_locals(binders);
+            RawDefn::Function {
+                name,
+                cgvars,
+                genvars,
+                args: args
+                    .into_iter()
+                    .map(|arg| {
+                        let ctx = arg.ctx();
+                        let mut arg = arg.deref().clone();
+                        arg.bind = inner.walk_type(arg.bind.clone());
+                        arg.with_ctx(ctx)
+                    })
+                    .collect(),
+                rettype: rettype.map(|rt| inner.walk_type(rt)),
+                body: inner.walk_expr(body),
+            }
+        }
+        RawDefn::Constant(name, body) => {
+            let mut inner = resolver.with_locals(std::iter::empty());
+            RawDefn::Constant(name, inner.walk_expr(body))
+        }
+        other => other,
+    }
+    .with_ctx(ctx)
+}
+
+/// The result of demodularizing a single top-level definition: either the (already fully resolved)
+/// definitions pulled in by a `require`, or one of the importing module's own definitions, which
+/// still needs its aliased references resolved.
+enum Contribution {
+    Imported(List<Ctx<RawDefn>>),
+    Own(Ctx<RawDefn>),
+}
+
+/// Rewrites qualified accesses `m.foo` (parsed as a field access on an alias variable) into the
+/// specific mangled symbol exported by the module bound to `m`.
+///
+/// A rewrite only fires when `m` is not a locally-bound name: a `let m = <struct> in m.foo`, or a
+/// function parameter named `m`, shadows an import alias `m`, so `m.foo` there is an ordinary field
+/// access and must be left alone. Binders are tracked the same way [Mangler] tracks its no-mangle
+/// scope.
+struct AliasResolver {
+    aliases: HashMap<(Symbol, Symbol), Symbol>,
+    /// Names bound locally on the current path, which shadow any import alias of the same name.
+    locals: Set<Symbol>,
+}
+
+impl AliasResolver {
+    fn new(aliases: HashMap<(Symbol, Symbol), Symbol>) -> Self {
+        Self {
+            aliases,
+            locals: Set::new(),
+        }
+    }
+
+    /// A child resolver with `syms` added to the locally-bound scope, used for a binder's body.
+    fn with_locals(&self, syms: impl IntoIterator<Item = Symbol>) -> Self {
+        let mut locals = self.locals.clone();
+        for sym in syms {
+            locals.insert(sym);
+        }
+        Self {
+            aliases: self.aliases.clone(),
+            locals,
+        }
     }
-    .with_ctx(bind.ctx())
-}
\ No newline at end of file
+}
+
+impl Visitor for AliasResolver {
+    fn walk_expr(&mut self, expr: Ctx<RawExpr>) -> Ctx<RawExpr> {
+        let ctx = expr.ctx();
+        match expr.deref().clone() {
+            RawExpr::Field(inner, field) => {
+                if let RawExpr::Var(alias) = inner.deref().clone() {
+                    if !self.locals.contains(&alias) {
+                        if let Some(target) = self.aliases.get(&(alias, field)) {
+                            return RawExpr::Var(*target).with_ctx(ctx);
+                        }
+                    }
+                }
+                descend_expr(self, RawExpr::Field(inner, field)).with_ctx(ctx)
+            }
+            // `let` binds a local name that shadows an import alias within the body
+            RawExpr::Let(sym, bind, body) => {
+                let bind = self.walk_expr(bind);
+                let body = self.with_locals(std::iter::once(*sym)).walk_expr(body);
+                RawExpr::Let(sym, bind, body).with_ctx(ctx)
+            }
+            other => descend_expr(self, other).with_ctx(ctx),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An in-memory [Resolver] mapping module ids to their source, for driving `demod` in tests
+    /// without touching the filesystem or network.
+    struct MemResolver {
+        sources: HashMap<ModuleId, String>,
+    }
+
+    impl Resolver for MemResolver {
+        fn resolve(&self, id: ModuleId) -> anyhow::Result<String> {
+            self.sources
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("no such module: {}", id))
+        }
+    }
+
+    /// Builds a demodularizer over the given `(module-id, source)` pairs.
+    fn demodularizer(sources: &[(&str, &str)]) -> Demodularizer {
+        let sources = sources
+            .iter()
+            .map(|(name, src)| (ModuleId::from(*name), (*src).to_string()))
+            .collect();
+        Demodularizer::new(Box::new(MemResolver { sources }))
+    }
+
+    /// Collects every symbol appearing in an expression position of a demodularized program.
+    fn program_vars(prog: &RawProgram) -> Set<Symbol> {
+        struct Spy {
+            seen: Set<Symbol>,
+        }
+        impl Visitor for Spy {
+            fn visit_sym(&mut self, sym: Symbol) -> Symbol {
+                self.seen.insert(sym);
+                sym
+            }
+        }
+        let mut spy = Spy { seen: Set::new() };
+        for def in prog.definitions.iter() {
+            match def.deref().clone() {
+                RawDefn::Function { body, .. } => {
+                    spy.walk_expr(body);
+                }
+                RawDefn::Constant(_, body) => {
+                    spy.walk_expr(body);
+                }
+                _ => {}
+            }
+        }
+        spy.walk_expr(prog.body.clone());
+        spy.seen
+    }
+
+    // chunk0-3: a module symbol referenced inside a `let` binding value must be mangled along with
+    // the definition it refers to, so the binding does not dangle after demodularization.
+    #[test]
+    fn let_binding_value_is_mangled() {
+        let demod = demodularizer(&[
+            (
+                "lib.mel",
+                "provide apply\n\
+                 def helper(x : Nat) = x * 2\n\
+                 def apply(y : Nat) = let z = helper(y) in z",
+            ),
+            ("main.mel", "require \"lib.mel\"\napply(21)"),
+        ]);
+        let prog = demod.demod(ModuleId::from("main.mel")).unwrap();
+        // `helper` is private, so it is mangled everywhere -- including inside the `let` value. If the
+        // binding were left unmangled it would still reference the bare name, which no longer exists.
+        assert!(!program_vars(&prog).contains(&Symbol::from("helper")));
+    }
+
+    /// The top-level names a demodularized program still defines.
+    fn def_names(prog: &RawProgram) -> Set<Symbol> {
+        prog.definitions
+            .iter()
+            .filter_map(|d| defn_ctx_name(d.deref()).map(|n| *n))
+            .collect()
+    }
+
+    // chunk0-2: a genuine `require` cycle is reported as a clean error with the full chain, not an
+    // unbounded recursion.
+    #[test]
+    fn real_cycle_is_reported() {
+        let demod = demodularizer(&[
+            ("a.mel", "require \"b.mel\"\n0"),
+            ("b.mel", "require \"a.mel\"\n0"),
+        ]);
+        let err = demod.demod(ModuleId::from("a.mel")).unwrap_err();
+        assert!(format!("{:?}", err).contains("import cycle detected"));
+    }
+
+    // chunk0-2: a non-cyclic diamond (A requires B and C, both requiring D) must resolve cleanly --
+    // the regression that the racy in-progress map turned into a spurious cycle.
+    #[test]
+    fn shared_diamond_resolves() {
+        let demod = demodularizer(&[
+            ("a.mel", "require \"b.mel\"\nrequire \"c.mel\"\n0"),
+            ("b.mel", "require \"d.mel\"\n0"),
+            ("c.mel", "require \"d.mel\"\n0"),
+            ("d.mel", "0"),
+        ]);
+        assert!(demod.demod(ModuleId::from("a.mel")).is_ok());
+    }
+
+    // chunk0-4: a provided constant keeps its public name while a private one is mangled, so
+    // constants demodularize like functions and structs rather than panicking.
+    #[test]
+    fn provided_constant_keeps_name_private_is_mangled() {
+        let demod = demodularizer(&[
+            ("lib.mel", "provide pi\ndef pi = 3\ndef secret = 2"),
+            ("main.mel", "require \"lib.mel\"\npi"),
+        ]);
+        let prog = demod.demod(ModuleId::from("main.mel")).unwrap();
+        let names = def_names(&prog);
+        assert!(names.contains(&Symbol::from("pi")));
+        assert!(!names.contains(&Symbol::from("secret")));
+    }
+
+    // chunk0-5: a selective import keeps only the requested provided name public; the rest are
+    // still emitted, but mangled, so no bare `bar` definition remains.
+    #[test]
+    fn selective_import_hides_unrequested() {
+        let demod = demodularizer(&[
+            (
+                "lib.mel",
+                "provide foo\nprovide bar\ndef foo(x : Nat) = x\ndef bar(x : Nat) = x",
+            ),
+            ("main.mel", "require (foo) from \"lib.mel\"\nfoo(1)"),
+        ]);
+        let prog = demod.demod(ModuleId::from("main.mel")).unwrap();
+        let names = def_names(&prog);
+        assert!(names.contains(&Symbol::from("foo")));
+        assert!(!names.contains(&Symbol::from("bar")));
+    }
+
+    // chunk0-5: a selective import keeps a requested provide's dependency on a *non-requested*
+    // provide resolvable -- the dependency is emitted (mangled), not dropped, so `foo`'s call to
+    // `baz` does not dangle. Its public unmangled name is still suppressed.
+    #[test]
+    fn selective_import_keeps_transitive_provide() {
+        let demod = demodularizer(&[
+            (
+                "lib.mel",
+                "provide foo\nprovide baz\ndef baz(x : Nat) = x\ndef foo(y : Nat) = baz(y)",
+            ),
+            ("main.mel", "require (foo) from \"lib.mel\"\nfoo(1)"),
+        ]);
+        let prog = demod.demod(ModuleId::from("main.mel")).unwrap();
+        let names = def_names(&prog);
+        // `foo` stays public, `baz` is emitted but mangled (no bare public name)
+        assert!(names.contains(&Symbol::from("foo")));
+        assert!(!names.contains(&Symbol::from("baz")));
+        // and every mangled `baz` reference in `foo`'s body resolves to an emitted definition
+        assert!(program_vars(&prog)
+            .iter()
+            .filter(|v| format!("{:?}", v).contains("baz"))
+            .all(|v| names.contains(v)));
+    }
+
+    // chunk0-5: an aliased import resolves `m.foo` to the module's exported symbol, leaving no
+    // dangling reference to the alias itself.
+    #[test]
+    fn aliased_import_resolves_qualified_access() {
+        let demod = demodularizer(&[
+            ("lib.mel", "provide foo\ndef foo(x : Nat) = x"),
+            ("main.mel", "require \"lib.mel\" as m\nm.foo(1)"),
+        ]);
+        let prog = demod.demod(ModuleId::from("main.mel")).unwrap();
+        assert!(!program_vars(&prog).contains(&Symbol::from("m")));
+    }
+
+    // chunk0-1: integrity annotations round-trip through hex and verify the exact bytes.
+    #[test]
+    fn integrity_parses_and_verifies() {
+        let digest = hex::encode(Sha256::digest(b"hello"));
+        let integrity = Integrity::parse(&format!("sha256:{}", digest)).unwrap();
+        assert!(integrity.verify(b"hello"));
+        assert!(!integrity.verify(b"goodbye"));
+        assert_eq!(integrity.to_hex(), digest);
+        assert!(Integrity::parse("md5:abcd").is_err());
+    }
+
+    // chunk0-1: remote ids are percent-encoded into safe on-disk cache filenames.
+    #[test]
+    fn cache_filename_is_percent_encoded() {
+        let integrity = Integrity::Sha256([0u8; 32]);
+        let name = cache_filename(ModuleId::from("https://example.com/utils.mel"), &integrity);
+        assert!(!name.contains('/'));
+        assert!(name.ends_with(&integrity.to_hex()));
+    }
+}